@@ -6,6 +6,7 @@ use std::default::Default;
 use std::error;
 use std::fmt::{self, Display};
 use std::os::unix::io::RawFd;
+use std::path::PathBuf;
 use std::str::FromStr;
 
 use audio_streams::{
@@ -18,6 +19,7 @@ use sys_util::{error, EventFd};
 use vm_memory::GuestMemory;
 
 use crate::pci::ac97_bus_master::Ac97BusMaster;
+use crate::pci::ac97_file::FileStreamSource;
 use crate::pci::ac97_mixer::Ac97Mixer;
 use crate::pci::ac97_regs::*;
 use crate::pci::pci_configuration::{
@@ -38,6 +40,7 @@ const PCI_DEVICE_ID_INTEL_82801AA_5: u16 = 0x2415;
 pub enum Ac97Backend {
     NULL,
     CRAS,
+    FILE,
 }
 
 impl Default for Ac97Backend {
@@ -57,7 +60,7 @@ impl error::Error for Ac97Error {}
 impl Display for Ac97Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Ac97Error::InvalidBackend => write!(f, "Must be cras or null"),
+            Ac97Error::InvalidBackend => write!(f, "Must be cras, null, or file"),
         }
     }
 }
@@ -68,6 +71,7 @@ impl FromStr for Ac97Backend {
         match s {
             "cras" => Ok(Ac97Backend::CRAS),
             "null" => Ok(Ac97Backend::NULL),
+            "file" => Ok(Ac97Backend::FILE),
             _ => Err(Ac97Error::InvalidBackend),
         }
     }
@@ -79,6 +83,9 @@ pub struct Ac97Parameters {
     pub backend: Ac97Backend,
     pub capture: bool,
     pub capture_effects: Vec<StreamEffect>,
+    // Destination file for the `FILE` backend. Guest playback is resampled and written here as a
+    // WAV file. Ignored by the other backends.
+    pub file: Option<PathBuf>,
 }
 
 pub struct Ac97Dev {
@@ -138,11 +145,27 @@ impl Ac97Dev {
         Ok(null_audio)
     }
 
+    fn create_file_audio_device(params: Ac97Parameters, mem: GuestMemory) -> Result<Ac97Dev> {
+        let path = params.file.ok_or_else(|| {
+            pci_device::Error::CreateFileStreamSourceFailed(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "no file path given for the FILE ac97 backend",
+            ))
+        })?;
+        let server = Box::new(
+            FileStreamSource::new(&path)
+                .map_err(pci_device::Error::CreateFileStreamSourceFailed)?,
+        );
+        let file_audio = Ac97Dev::new(mem, server);
+        Ok(file_audio)
+    }
+
     /// Creates an 'Ac97Dev' with suitable audio server inside based on Ac97Parameters
     pub fn try_new(mem: GuestMemory, param: Ac97Parameters) -> Result<Ac97Dev> {
         match param.backend {
             Ac97Backend::CRAS => Ac97Dev::create_cras_audio_device(param, mem),
             Ac97Backend::NULL => Ac97Dev::create_null_audio_device(mem),
+            Ac97Backend::FILE => Ac97Dev::create_file_audio_device(param, mem),
         }
     }
 
@@ -0,0 +1,396 @@
+// Copyright 2020 The Chromium OS Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! A file-backed `ShmStreamSource` that captures guest playback to a WAV file.
+//!
+//! Unlike the `CRAS` and `NULL` backends, the `FILE` backend has no live device: it drains the
+//! guest's PCM output into a growing WAV file so audio can be captured deterministically for
+//! testing or recording. Because the sample rate the guest programs into the AC97 controller need
+//! not match the file's output rate, incoming samples are staged in a ring-buffer FIFO that
+//! linear-interpolates to the output rate before complete frames are flushed to disk -- the same
+//! FIFO-buffered staging a software audio encoder uses between a fixed-rate sink and a
+//! variable-rate producer.
+//!
+//! Two invariants guard the bus-master DMA completion path:
+//!   * The FIFO never blocks that path: when a pushed block takes it past its capacity, the oldest
+//!     staged samples are dropped. The resampled output is written to the file synchronously from
+//!     the completion callback, so disk latency is not absorbed by the FIFO.
+//!   * The WAV header's sample count is finalized when the device is torn down, so the file is a
+//!     valid WAV even though its length is not known up front.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use audio_streams::shm_streams::{BufferComplete, ServerRequest, ShmStream, ShmStreamSource};
+use audio_streams::{BoxError, SampleFormat, StreamDirection, StreamEffect};
+use sys_util::{MemoryMapping, SharedMemory};
+
+/// The rate the captured WAV file is written at. The guest's programmed rate is resampled to this.
+const FILE_OUTPUT_RATE: u32 = 48_000;
+
+/// Upper bound on how many frames the resampling FIFO stages before it starts dropping the oldest
+/// samples, so an oversized block cannot make the FIFO grow without bound.
+const FIFO_CAPACITY_FRAMES: usize = FILE_OUTPUT_RATE as usize; // ~1s at the output rate.
+
+/// A minimal little-endian PCM WAV writer. The RIFF and data chunk sizes are written as
+/// placeholders up front and patched in [`WavWriter::finalize`] once the total sample count is
+/// known.
+struct WavWriter {
+    file: File,
+    num_channels: u16,
+    frame_rate: u32,
+    // Total number of sample bytes written to the data chunk.
+    data_bytes: u32,
+    finalized: bool,
+}
+
+const WAV_HEADER_LEN: u32 = 44;
+const BITS_PER_SAMPLE: u16 = 16;
+
+impl WavWriter {
+    fn new(path: &Path, num_channels: u16, frame_rate: u32) -> io::Result<Self> {
+        let file = File::create(path)?;
+        let mut writer = WavWriter {
+            file,
+            num_channels,
+            frame_rate,
+            data_bytes: 0,
+            finalized: false,
+        };
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let byte_rate =
+            self.frame_rate * u32::from(self.num_channels) * u32::from(BITS_PER_SAMPLE) / 8;
+        let block_align = self.num_channels * BITS_PER_SAMPLE / 8;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(b"RIFF")?;
+        // RIFF chunk size: patched on finalize.
+        self.file.write_all(&(WAV_HEADER_LEN - 8).to_le_bytes())?;
+        self.file.write_all(b"WAVE")?;
+        self.file.write_all(b"fmt ")?;
+        self.file.write_all(&16u32.to_le_bytes())?; // PCM fmt chunk size.
+        self.file.write_all(&1u16.to_le_bytes())?; // Audio format: PCM.
+        self.file.write_all(&self.num_channels.to_le_bytes())?;
+        self.file.write_all(&self.frame_rate.to_le_bytes())?;
+        self.file.write_all(&byte_rate.to_le_bytes())?;
+        self.file.write_all(&block_align.to_le_bytes())?;
+        self.file.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+        self.file.write_all(b"data")?;
+        // data chunk size: patched on finalize.
+        self.file.write_all(&0u32.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        for sample in samples {
+            self.file.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_bytes += (samples.len() * 2) as u32;
+        Ok(())
+    }
+
+    /// Patches the RIFF and data chunk sizes to reflect everything written so far. Idempotent.
+    fn finalize(&mut self) -> io::Result<()> {
+        if self.finalized {
+            return Ok(());
+        }
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file
+            .write_all(&(WAV_HEADER_LEN - 8 + self.data_bytes).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&self.data_bytes.to_le_bytes())?;
+        self.file.flush()?;
+        self.finalized = true;
+        Ok(())
+    }
+}
+
+impl Drop for WavWriter {
+    fn drop(&mut self) {
+        // The capture file must be a valid WAV even if the device is torn down mid-stream.
+        if let Err(e) = self.finalize() {
+            sys_util::error!("failed to finalize WAV capture file: {}", e);
+        }
+    }
+}
+
+/// A ring-buffer FIFO that accepts interleaved PCM frames at `in_rate` and produces them at
+/// `out_rate` using per-channel linear interpolation.
+struct ResamplingFifo {
+    num_channels: usize,
+    // Interleaved samples awaiting resampling.
+    buffer: VecDeque<i16>,
+    // Input frames consumed per output frame.
+    step: f64,
+    // Fractional read position, in input frames, relative to the front of `buffer`.
+    pos: f64,
+    // Maximum number of frames retained before the oldest are dropped.
+    capacity_frames: usize,
+}
+
+impl ResamplingFifo {
+    fn new(num_channels: usize, in_rate: u32, out_rate: u32) -> Self {
+        ResamplingFifo {
+            num_channels,
+            buffer: VecDeque::new(),
+            step: f64::from(in_rate) / f64::from(out_rate),
+            pos: 0.0,
+            capacity_frames: FIFO_CAPACITY_FRAMES,
+        }
+    }
+
+    fn frames(&self) -> usize {
+        self.buffer.len() / self.num_channels
+    }
+
+    fn sample(&self, frame: usize, channel: usize) -> i16 {
+        self.buffer[frame * self.num_channels + channel]
+    }
+
+    /// Stages a block of interleaved input frames. Never blocks: if the FIFO is already at capacity
+    /// the oldest frames are dropped so the DMA completion path is not stalled.
+    fn push(&mut self, samples: &[i16]) {
+        self.buffer.extend(samples.iter().copied());
+        let frames = self.frames();
+        if frames > self.capacity_frames {
+            let drop_frames = frames - self.capacity_frames;
+            for _ in 0..drop_frames * self.num_channels {
+                self.buffer.pop_front();
+            }
+            // Keep the read position anchored to the front after dropping.
+            self.pos = (self.pos - drop_frames as f64).max(0.0);
+        }
+    }
+
+    /// Pulls all output frames that can be interpolated from the currently staged input, appending
+    /// interleaved samples to `out`. Consumed input frames are removed from the front.
+    fn resample_into(&mut self, out: &mut Vec<i16>) {
+        while self.pos + 1.0 < self.frames() as f64 {
+            let i0 = self.pos.floor() as usize;
+            let frac = self.pos - i0 as f64;
+            for c in 0..self.num_channels {
+                let a = f64::from(self.sample(i0, c));
+                let b = f64::from(self.sample(i0 + 1, c));
+                out.push((a + (b - a) * frac).round() as i16);
+            }
+            self.pos += self.step;
+        }
+        let consumed = self.pos.floor() as usize;
+        if consumed > 0 {
+            for _ in 0..consumed * self.num_channels {
+                self.buffer.pop_front();
+            }
+            self.pos -= consumed as f64;
+        }
+    }
+}
+
+/// Creates file-backed capture streams.
+pub struct FileStreamSource {
+    path: std::path::PathBuf,
+}
+
+impl FileStreamSource {
+    pub fn new(path: &Path) -> io::Result<Self> {
+        Ok(FileStreamSource {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl ShmStreamSource for FileStreamSource {
+    fn new_stream(
+        &mut self,
+        _direction: StreamDirection,
+        num_channels: usize,
+        _format: SampleFormat,
+        frame_rate: u32,
+        buffer_size: usize,
+        _effects: &[StreamEffect],
+        client_shm: &SharedMemory,
+        _buffer_offsets: [u64; 2],
+    ) -> Result<Box<dyn ShmStream>, BoxError> {
+        let writer = WavWriter::new(&self.path, num_channels as u16, FILE_OUTPUT_RATE)?;
+        let fifo = ResamplingFifo::new(num_channels, frame_rate, FILE_OUTPUT_RATE);
+        // Map the buffer the bus master fills so the completion callback can read the guest PCM
+        // back out of it.
+        let client_shm = MemoryMapping::from_fd(client_shm, client_shm.size() as usize)?;
+        // One request carries `buffer_size` frames; pace requests at the rate the guest consumes
+        // them so the FIFO is fed at roughly the programmed sample rate.
+        let interval = Duration::from_nanos(
+            1_000_000_000u64 * buffer_size as u64 / u64::from(frame_rate.max(1)),
+        );
+        Ok(Box::new(FileStream {
+            num_channels,
+            frame_rate,
+            buffer_size,
+            writer,
+            fifo,
+            client_shm,
+            interval,
+            next_frame: interval,
+            start_time: None,
+        }))
+    }
+
+    fn keep_fds(&self) -> Vec<RawFd> {
+        Vec::new()
+    }
+}
+
+/// A single capture stream: it stages each guest playback buffer through the resampling FIFO and
+/// flushes complete output frames to the WAV file.
+struct FileStream {
+    num_channels: usize,
+    frame_rate: u32,
+    buffer_size: usize,
+    writer: WavWriter,
+    fifo: ResamplingFifo,
+    // The guest-shared buffer the bus master fills; read back in the completion callback.
+    client_shm: MemoryMapping,
+    // How long a full `buffer_size` request is expected to take at the programmed rate.
+    interval: Duration,
+    // When the next request becomes available, measured from `start_time`.
+    next_frame: Duration,
+    // Set when the first request is served.
+    start_time: Option<Instant>,
+}
+
+impl FileStream {
+    /// Feeds a block of interleaved guest PCM into the FIFO and writes whatever resamples out of it
+    /// to the capture file. The write is synchronous: a slow disk delays the return of this call,
+    /// and the FIFO does not absorb that delay.
+    fn capture_frames(&mut self, frames: &[i16]) {
+        self.fifo.push(frames);
+        let mut out = Vec::new();
+        self.fifo.resample_into(&mut out);
+        if !out.is_empty() {
+            if let Err(e) = self.writer.write_samples(&out) {
+                sys_util::error!("failed to write captured audio: {}", e);
+            }
+        }
+    }
+}
+
+impl BufferComplete for FileStream {
+    /// Invoked once the bus master has filled `frames` of PCM at `offset` in the shared buffer.
+    /// Reads that PCM back out and stages it through the FIFO. Reads never fail the DMA path: a bad
+    /// offset is logged and the block dropped.
+    fn callback(&mut self, offset: usize, frames: usize) {
+        let num_samples = frames * self.num_channels;
+        let mut samples = vec![0i16; num_samples];
+        match self.client_shm.get_slice(offset as u64, num_samples * 2) {
+            Ok(slice) => slice.copy_to(&mut samples),
+            Err(e) => {
+                sys_util::error!("failed to read captured audio from shared buffer: {}", e);
+                return;
+            }
+        }
+        self.capture_frames(&samples);
+    }
+}
+
+impl ShmStream for FileStream {
+    fn frame_size(&self) -> usize {
+        self.num_channels * (BITS_PER_SAMPLE as usize / 8)
+    }
+
+    fn num_channels(&self) -> usize {
+        self.num_channels
+    }
+
+    fn frame_rate(&self) -> u32 {
+        self.frame_rate
+    }
+
+    fn wait_for_next_action_with_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<ServerRequest>, BoxError> {
+        // Pace requests so the guest sees the buffer drained at roughly the rate it programmed,
+        // then hand one out covering `buffer_size` frames. The bus master fills the shared buffer
+        // and completes the request, whose callback reads the just-written playback frames back out
+        // and routes them through `capture_frames`.
+        if let Some(start_time) = self.start_time {
+            let elapsed = start_time.elapsed();
+            if elapsed < self.next_frame {
+                let remaining = self.next_frame - elapsed;
+                if timeout < remaining {
+                    std::thread::sleep(timeout);
+                    return Ok(None);
+                }
+                std::thread::sleep(remaining);
+            }
+        } else {
+            self.start_time = Some(Instant::now());
+        }
+        self.next_frame += self.interval;
+        Ok(Some(ServerRequest::new(self.buffer_size, self)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resample_identity_rate_is_passthrough() {
+        let mut fifo = ResamplingFifo::new(1, 48_000, 48_000);
+        fifo.push(&[0, 100, 200, 300, 400]);
+        let mut out = Vec::new();
+        fifo.resample_into(&mut out);
+        // With a 1:1 ratio every input frame but the trailing one (needed for interpolation) is
+        // reproduced exactly.
+        assert_eq!(out, vec![0, 100, 200, 300]);
+    }
+
+    #[test]
+    fn resample_downsamples_by_two() {
+        let mut fifo = ResamplingFifo::new(1, 48_000, 24_000);
+        fifo.push(&[0, 100, 200, 300, 400]);
+        let mut out = Vec::new();
+        fifo.resample_into(&mut out);
+        assert_eq!(out, vec![0, 200]);
+    }
+
+    #[test]
+    fn resample_interleaves_channels() {
+        let mut fifo = ResamplingFifo::new(2, 48_000, 48_000);
+        fifo.push(&[0, 1, 10, 11, 20, 21]);
+        let mut out = Vec::new();
+        fifo.resample_into(&mut out);
+        assert_eq!(out, vec![0, 1, 10, 11]);
+    }
+
+    #[test]
+    fn fifo_drops_oldest_under_backpressure() {
+        let mut fifo = ResamplingFifo::new(1, 48_000, 48_000);
+        fifo.capacity_frames = 4;
+        fifo.push(&[0, 1, 2, 3, 4, 5]);
+        // Only the newest `capacity_frames` frames are retained.
+        assert_eq!(fifo.frames(), 4);
+        assert_eq!(fifo.sample(0, 0), 2);
+    }
+
+    #[test]
+    fn wav_header_is_finalized_on_drop() {
+        let dir = sys_util::TempDir::new("ac97_file_test").unwrap();
+        let path = dir.as_path().unwrap().join("capture.wav");
+        {
+            let mut writer = WavWriter::new(&path, 2, FILE_OUTPUT_RATE).unwrap();
+            writer.write_samples(&[0, 0, 1, 1]).unwrap();
+        }
+        let contents = std::fs::read(&path).unwrap();
+        // data chunk size is stored at byte 40 and must reflect the 8 bytes written.
+        let data_size = u32::from_le_bytes([contents[40], contents[41], contents[42], contents[43]]);
+        assert_eq!(data_size, 8);
+    }
+}
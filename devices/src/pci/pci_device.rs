@@ -0,0 +1,85 @@
+// Copyright 2018 The Chromium OS Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+use std::error;
+use std::fmt::{self, Display};
+use std::io;
+use std::os::unix::io::RawFd;
+
+use resources::SystemAllocator;
+use sys_util::EventFd;
+
+use crate::pci::pci_configuration;
+use crate::pci::{PciAddress, PciInterruptPin};
+
+/// Errors that a `PciDevice` implementation can encounter while being set up.
+#[derive(Debug)]
+pub enum Error {
+    /// Setting up the CRAS client for an Ac97 device failed.
+    CreateCrasClientFailed(libcras::Error),
+    /// Setting up the file-backed capture stream for an Ac97 device failed.
+    CreateFileStreamSourceFailed(io::Error),
+    /// Allocating an MMIO range of `size` for a BAR failed.
+    IoAllocationFailed(u64, resources::Error),
+    /// Registering an MMIO range at `address` as a BAR failed.
+    IoRegistrationFailed(u64, pci_configuration::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl error::Error for Error {}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::CreateCrasClientFailed(e) => write!(f, "failed to create CRAS client: {}", e),
+            Error::CreateFileStreamSourceFailed(e) => {
+                write!(f, "failed to create file stream source: {}", e)
+            }
+            Error::IoAllocationFailed(size, e) => {
+                write!(f, "failed to allocate {} bytes for BAR: {}", size, e)
+            }
+            Error::IoRegistrationFailed(addr, e) => {
+                write!(f, "failed to register BAR at 0x{:x}: {}", addr, e)
+            }
+        }
+    }
+}
+
+/// A PCI device that can be plugged into crosvm's PCI bus.
+pub trait PciDevice: Send {
+    /// A short label for this device, used in logging and error messages.
+    fn debug_label(&self) -> String;
+
+    /// Assigns the bus/device/function address this device was placed at.
+    fn assign_address(&mut self, address: PciAddress);
+
+    /// Assigns the legacy INTx# interrupt this device will use.
+    fn assign_irq(
+        &mut self,
+        irq_evt: EventFd,
+        irq_resample_evt: EventFd,
+        irq_num: u32,
+        irq_pin: PciInterruptPin,
+    );
+
+    /// Allocates the MMIO regions this device's BARs need and registers them in its
+    /// configuration space, returning the `(address, size)` of each allocated range.
+    fn allocate_io_bars(&mut self, resources: &mut SystemAllocator) -> Result<Vec<(u64, u64)>>;
+
+    /// Reads the configuration space register at `reg_idx`.
+    fn read_config_register(&self, reg_idx: usize) -> u32;
+
+    /// Writes `data` at `offset` into the configuration space register at `reg_idx`.
+    fn write_config_register(&mut self, reg_idx: usize, offset: u64, data: &[u8]);
+
+    /// Returns the FDs this device needs to keep open across a jail fork.
+    fn keep_fds(&self) -> Vec<RawFd>;
+
+    /// Reads `data` from this device's BAR region at `addr`.
+    fn read_bar(&mut self, addr: u64, data: &mut [u8]);
+
+    /// Writes `data` to this device's BAR region at `addr`.
+    fn write_bar(&mut self, addr: u64, data: &[u8]);
+}
@@ -5,36 +5,862 @@
 //! Implementation of the the `Encoder` struct, which is responsible for translation between the
 //! virtio protocols and LibVDA APIs.
 
-use sys_util::PollContext;
+use std::collections::{BTreeMap, BTreeSet};
 
-use crate::virtio::resource_bridge::ResourceRequestSocket;
-use crate::virtio::video::command::VideoCmd;
-use crate::virtio::video::device::{Device, Token, VideoCmdResponseType, VideoEvtResponseType};
+use libvda::encode::{EncodeCapabilities, VeaImplType, VeaInstance};
+use sys_util::{error, PollContext, WatchingEvents};
+
+use crate::virtio::resource_bridge::{self, ResourceInfo, ResourceRequestSocket};
+use crate::virtio::video::command::{QueueType, VideoCmd};
+use crate::virtio::video::control::{CtrlType, CtrlVal, QueryCtrlType};
+use crate::virtio::video::device::{
+    AsyncCmdTag, Device, Token, VideoCmdResponseType, VideoEvtResponseType,
+};
 use crate::virtio::video::error::*;
+use crate::virtio::video::event::{EvtType, VideoEvt};
+use crate::virtio::video::format::{
+    BitrateMode, Format, FormatDesc, FormatRange, FrameFormat, Level, PlaneFormat, Profile,
+    RateControl,
+};
+use crate::virtio::video::params::Params;
+use crate::virtio::video::response::CmdResponse;
+
+/// An input resource that holds a raw frame queued by the guest, waiting to be handed to the
+/// LibVDA encode session.
+struct InputResource {
+    resource_id: u32,
+    timestamp: u64,
+    planes: Vec<PlaneFormat>,
+}
+
+/// An output resource that backs a bitstream buffer the encoder writes coded data into.
+struct OutputResource {
+    resource_id: u32,
+}
+
+/// Per-`stream_id` encode state: the LibVDA session and its pending input/output queues.
+struct Stream {
+    input_format: Format,
+    output_format: Format,
+    // The coded profile this session was opened with, and the level the guest has negotiated
+    // against it (validated against `profile.supported_levels()`).
+    profile: Profile,
+    level: Option<Level>,
+    dst_params: Params,
+    src_params: Params,
+    // The LibVDA encode session backing this stream.
+    session: libvda::encode::Session,
+    // Raw input frames queued by the guest but not yet submitted to the session.
+    input_queue: Vec<InputResource>,
+    // Output bitstream buffers queued by the guest but not yet handed to the session.
+    output_queue: Vec<OutputResource>,
+    // The set of input resources currently owned by the session.
+    input_resource_ids: BTreeSet<u32>,
+    // The set of output resources currently owned by the session.
+    output_resource_ids: BTreeSet<u32>,
+    // Set once LibVDA has reported `RequireInputBuffers` for this stream; until then the guest's
+    // queued buffers cannot be submitted.
+    require_input_buffers_received: bool,
+    // The output resource that will carry the EOS marker once the pending drain completes.
+    eos_notification_buffer: Option<u32>,
+    // True while a `STREAM_DRAIN` is outstanding.
+    draining: bool,
+    // The rate control the session was opened with, updated in place when the guest reconfigures
+    // it mid-stream.
+    rate_control: RateControl,
+}
+
+impl Stream {
+    fn new(
+        stream_id: u32,
+        instance: &VeaInstance,
+        input_format: Format,
+        output_profile: Profile,
+        level: Option<Level>,
+        rate_control: RateControl,
+    ) -> VideoResult<Self> {
+        let profile = output_profile
+            .to_libvda_profile()
+            .ok_or(VideoError::InvalidArgument)?;
+        let config = libvda::encode::Config {
+            input_format: libvda_input_format(input_format)?,
+            input_visible_width: 0,
+            input_visible_height: 0,
+            output_profile: profile,
+            bitrate: rate_control.to_libvda_bitrate(),
+            initial_framerate: Some(rate_control.framerate),
+            h264_output_level: level.and_then(|l| l.to_libvda_h264_level()),
+        };
+        let session = instance.open_session(config).map_err(|e| {
+            error!("failed to open encode session for stream {}: {}", stream_id, e);
+            VideoError::InvalidOperation
+        })?;
+        Ok(Stream {
+            input_format,
+            output_format: output_profile.to_format(),
+            profile: output_profile,
+            level,
+            dst_params: Default::default(),
+            src_params: Default::default(),
+            session,
+            input_queue: Default::default(),
+            output_queue: Default::default(),
+            input_resource_ids: Default::default(),
+            output_resource_ids: Default::default(),
+            require_input_buffers_received: false,
+            eos_notification_buffer: None,
+            draining: false,
+            rate_control,
+        })
+    }
+}
+
+const DEFAULT_BITRATE: u32 = 6000;
+const DEFAULT_FRAMERATE: u32 = 30;
+
+/// The rate control a stream starts with until the guest reconfigures it through the control-set
+/// path: constant bitrate at the default bitrate/framerate.
+fn default_rate_control() -> RateControl {
+    RateControl {
+        mode: BitrateMode::Cbr,
+        target_bitrate: DEFAULT_BITRATE,
+        peak_bitrate: None,
+        framerate: DEFAULT_FRAMERATE,
+    }
+}
 
-pub struct Encoder;
+fn libvda_input_format(format: Format) -> VideoResult<libvda::PixelFormat> {
+    match format {
+        Format::NV12 => Ok(libvda::PixelFormat::NV12),
+        Format::YUV420 => Ok(libvda::PixelFormat::YV12),
+        _ => Err(VideoError::InvalidArgument),
+    }
+}
+
+pub struct Encoder {
+    instance: VeaInstance,
+    capabilities: EncoderCapabilities,
+    streams: BTreeMap<u32, Stream>,
+}
 
 impl Encoder {
-    pub fn new() -> Self {
-        Encoder {}
+    pub fn new() -> VideoResult<Self> {
+        let instance = VeaInstance::new(VeaImplType::Libvda).map_err(|e| {
+            error!("failed to create VEA instance: {}", e);
+            VideoError::InvalidOperation
+        })?;
+        let capabilities = EncoderCapabilities::from_libvda(instance.get_capabilities());
+        Ok(Encoder {
+            instance,
+            capabilities,
+            streams: Default::default(),
+        })
+    }
+
+    fn get_stream(&mut self, stream_id: u32) -> VideoResult<&mut Stream> {
+        self.streams
+            .get_mut(&stream_id)
+            .ok_or(VideoError::InvalidStreamId(stream_id))
+    }
+
+    /// Applies a guest-requested coded profile to a stream by re-opening its LibVDA session. Only
+    /// valid before the backend has taken ownership of any buffers: the profile is fixed at session
+    /// open time and cannot change once frames are in flight.
+    fn set_profile(
+        &mut self,
+        stream_id: u32,
+        profile: Profile,
+        poll_ctx: &PollContext<Token>,
+    ) -> VideoResult<VideoCmdResponseType> {
+        let (input_format, output_format, rate_control, reconfigurable) = {
+            let stream = self.get_stream(stream_id)?;
+            (
+                stream.input_format,
+                stream.output_format,
+                stream.rate_control,
+                !stream.require_input_buffers_received
+                    && stream.input_queue.is_empty()
+                    && stream.output_queue.is_empty(),
+            )
+        };
+        // The requested profile must belong to the coded format the stream was created with.
+        if profile.to_format() != output_format {
+            return Err(VideoError::InvalidArgument);
+        }
+        if !reconfigurable {
+            return Err(VideoError::InvalidOperation);
+        }
+        // Open the replacement session before disturbing the old one so a failure leaves the stream
+        // untouched. The previously negotiated level, if any, may not be valid for the new profile,
+        // so the guest must renegotiate it.
+        let new_stream = Stream::new(
+            stream_id,
+            &self.instance,
+            input_format,
+            profile,
+            None,
+            rate_control,
+        )?;
+        let stream = self.get_stream(stream_id)?;
+        poll_ctx
+            .delete(&stream.session)
+            .map_err(VideoError::SysError)?;
+        poll_ctx
+            .add_fd_with_events(
+                &new_stream.session,
+                WatchingEvents::empty().set_read(),
+                Token::Event { id: stream_id },
+            )
+            .map_err(VideoError::SysError)?;
+        *stream = new_stream;
+        Ok(VideoCmdResponseType::Sync(CmdResponse::NoData))
+    }
+
+    /// Applies a guest-requested level to a stream by re-opening its LibVDA session, the same way
+    /// `set_profile` applies a profile. Only valid before the backend has taken ownership of any
+    /// buffers, and only for a level the stream's current profile actually supports.
+    fn set_level(
+        &mut self,
+        stream_id: u32,
+        level: Level,
+        poll_ctx: &PollContext<Token>,
+    ) -> VideoResult<VideoCmdResponseType> {
+        let (input_format, profile, rate_control, reconfigurable) = {
+            let stream = self.get_stream(stream_id)?;
+            (
+                stream.input_format,
+                stream.profile,
+                stream.rate_control,
+                !stream.require_input_buffers_received
+                    && stream.input_queue.is_empty()
+                    && stream.output_queue.is_empty(),
+            )
+        };
+        if !profile.supported_levels().contains(&level) {
+            return Err(VideoError::InvalidArgument);
+        }
+        if !reconfigurable {
+            return Err(VideoError::InvalidOperation);
+        }
+        // Open the replacement session before disturbing the old one so a failure leaves the stream
+        // untouched.
+        let new_stream = Stream::new(
+            stream_id,
+            &self.instance,
+            input_format,
+            profile,
+            Some(level),
+            rate_control,
+        )?;
+        let stream = self.get_stream(stream_id)?;
+        poll_ctx
+            .delete(&stream.session)
+            .map_err(VideoError::SysError)?;
+        poll_ctx
+            .add_fd_with_events(
+                &new_stream.session,
+                WatchingEvents::empty().set_read(),
+                Token::Event { id: stream_id },
+            )
+            .map_err(VideoError::SysError)?;
+        *stream = new_stream;
+        Ok(VideoCmdResponseType::Sync(CmdResponse::NoData))
+    }
+}
+
+/// The input and output `FormatDesc`s advertised to the guest, derived once from LibVDA's
+/// reported encode capabilities.
+struct EncoderCapabilities {
+    input_format_descs: Vec<FormatDesc>,
+    output_format_descs: Vec<FormatDesc>,
+}
+
+impl EncoderCapabilities {
+    fn from_libvda(caps: &EncodeCapabilities) -> Self {
+        // LibVDA reports, per output profile, the maximum coded frame size and bitrate; we fold
+        // those into one `FrameFormat` per coded `Format` along with the raw input formats the
+        // backend can consume.
+        let mut output: BTreeMap<Format, FrameFormat> = BTreeMap::new();
+        for out in caps.output_formats.iter() {
+            let profile = match Profile::from_libvda_profile(out.profile) {
+                Some(p) => p,
+                None => continue,
+            };
+            let format = profile.to_format();
+            let max_level = profile.supported_levels().last().copied();
+            let entry = output.entry(format).or_insert_with(|| FrameFormat {
+                width: FormatRange {
+                    min: 0,
+                    max: 0,
+                    step: 1,
+                },
+                height: FormatRange {
+                    min: 0,
+                    max: 0,
+                    step: 1,
+                },
+                bitrates: vec![FormatRange {
+                    min: 0,
+                    max: out.max_bitrate,
+                    step: 1,
+                }],
+                // All LibVDA encoders accept both constant and variable bitrate control.
+                bitrate_modes: vec![BitrateMode::Cbr, BitrateMode::Vbr],
+                max_level,
+            });
+            entry.width.max = std::cmp::max(entry.width.max, out.max_width);
+            entry.height.max = std::cmp::max(entry.height.max, out.max_height);
+            entry.bitrates[0].max = std::cmp::max(entry.bitrates[0].max, out.max_bitrate);
+        }
+
+        let output_format_descs = output
+            .into_iter()
+            .map(|(format, frame_format)| FormatDesc {
+                mask: 0,
+                format,
+                frame_formats: vec![frame_format],
+            })
+            .collect();
+
+        let input_format_descs = caps
+            .input_formats
+            .iter()
+            .filter_map(|pf| match pf {
+                libvda::PixelFormat::NV12 => Some(Format::NV12),
+                libvda::PixelFormat::YV12 => Some(Format::YUV420),
+                _ => None,
+            })
+            .map(|format| FormatDesc {
+                mask: 0,
+                format,
+                frame_formats: vec![Default::default()],
+            })
+            .collect();
+
+        EncoderCapabilities {
+            input_format_descs,
+            output_format_descs,
+        }
+    }
+
+    fn query_capability(&self, queue_type: QueueType) -> Vec<FormatDesc> {
+        match queue_type {
+            // For an encoder the raw frames are the guest's input queue and the coded bitstream is
+            // the output queue.
+            QueueType::Input => self.input_format_descs.clone(),
+            QueueType::Output => self.output_format_descs.clone(),
+        }
     }
 }
 
 impl Device for Encoder {
     fn process_cmd(
         &mut self,
-        _cmd: VideoCmd,
-        _poll_ctx: &PollContext<Token>,
-        _resource_bridge: &ResourceRequestSocket,
+        cmd: VideoCmd,
+        poll_ctx: &PollContext<Token>,
+        resource_bridge: &ResourceRequestSocket,
     ) -> VideoResult<VideoCmdResponseType> {
-        Err(VideoError::InvalidOperation)
+        use VideoCmd::*;
+        match cmd {
+            QueryCapability { queue_type } => Ok(VideoCmdResponseType::Sync(
+                CmdResponse::QueryCapability(self.capabilities.query_capability(queue_type)),
+            )),
+            StreamCreate {
+                stream_id,
+                coded_format: output_format,
+                input_format,
+            } => {
+                if self.streams.contains_key(&stream_id) {
+                    return Err(VideoError::InvalidStreamId(stream_id));
+                }
+                // The coded format the guest asked for must map to a profile the backend supports.
+                let profile = self
+                    .capabilities
+                    .output_format_descs
+                    .iter()
+                    .find(|d| d.format == output_format)
+                    .and_then(|_| default_profile_for(output_format))
+                    .ok_or(VideoError::InvalidArgument)?;
+                let stream = Stream::new(
+                    stream_id,
+                    &self.instance,
+                    input_format,
+                    profile,
+                    None,
+                    default_rate_control(),
+                )?;
+                // Watch the session's event FD so `process_event_fd` is pumped for this stream.
+                poll_ctx
+                    .add_fd_with_events(
+                        &stream.session,
+                        WatchingEvents::empty().set_read(),
+                        Token::Event { id: stream_id },
+                    )
+                    .map_err(VideoError::SysError)?;
+                self.streams.insert(stream_id, stream);
+                Ok(VideoCmdResponseType::Sync(CmdResponse::NoData))
+            }
+            StreamDestroy { stream_id } => {
+                // Dropping the stream drops the LibVDA session, which closes the backend context.
+                self.streams.remove(&stream_id);
+                Ok(VideoCmdResponseType::Sync(CmdResponse::NoData))
+            }
+            StreamDrain { stream_id } => {
+                let stream = self.get_stream(stream_id)?;
+                stream.draining = true;
+                stream.session.flush().map_err(|e| {
+                    error!("failed to flush encode session {}: {}", stream_id, e);
+                    VideoError::InvalidOperation
+                })?;
+                Ok(VideoCmdResponseType::Async(AsyncCmdTag::Drain { stream_id }))
+            }
+            ResourceCreate {
+                stream_id,
+                queue_type,
+                resource_id,
+                plane_offsets: _,
+                uuid: _,
+            } => {
+                let stream = self.get_stream(stream_id)?;
+                match queue_type {
+                    QueueType::Input => stream.input_resource_ids.insert(resource_id),
+                    QueueType::Output => stream.output_resource_ids.insert(resource_id),
+                };
+                Ok(VideoCmdResponseType::Sync(CmdResponse::NoData))
+            }
+            ResourceQueue {
+                stream_id,
+                queue_type,
+                resource_id,
+                timestamp,
+                data_sizes,
+            } => {
+                let tag = AsyncCmdTag::Queue {
+                    stream_id,
+                    queue_type,
+                    resource_id,
+                };
+                let stream = self.get_stream(stream_id)?;
+                match queue_type {
+                    QueueType::Input => {
+                        let planes = data_sizes
+                            .iter()
+                            .map(|&plane_size| PlaneFormat {
+                                plane_size,
+                                stride: 0,
+                            })
+                            .collect();
+                        stream.input_queue.push(InputResource {
+                            resource_id,
+                            timestamp,
+                            planes,
+                        });
+                    }
+                    QueueType::Output => {
+                        stream.output_queue.push(OutputResource { resource_id });
+                    }
+                }
+                try_submit_queued(stream, resource_bridge)?;
+                Ok(VideoCmdResponseType::Async(tag))
+            }
+            ResourceDestroyAll {
+                stream_id,
+                queue_type,
+            } => {
+                let stream = self.get_stream(stream_id)?;
+                match queue_type {
+                    QueueType::Input => {
+                        stream.input_queue.clear();
+                        stream.input_resource_ids.clear();
+                    }
+                    QueueType::Output => {
+                        stream.output_queue.clear();
+                        stream.output_resource_ids.clear();
+                    }
+                }
+                Ok(VideoCmdResponseType::Sync(CmdResponse::NoData))
+            }
+            GetParams {
+                stream_id,
+                queue_type,
+            } => {
+                let stream = self.get_stream(stream_id)?;
+                let params = match queue_type {
+                    QueueType::Input => stream.src_params.clone(),
+                    QueueType::Output => stream.dst_params.clone(),
+                };
+                Ok(VideoCmdResponseType::Sync(CmdResponse::GetParams {
+                    queue_type,
+                    params,
+                }))
+            }
+            SetParams {
+                stream_id,
+                queue_type,
+                params,
+            } => {
+                let stream = self.get_stream(stream_id)?;
+                match queue_type {
+                    QueueType::Input => stream.src_params = params,
+                    QueueType::Output => stream.dst_params = params,
+                }
+                Ok(VideoCmdResponseType::Sync(CmdResponse::NoData))
+            }
+            QueryControl { query_ctrl_type } => match query_ctrl_type {
+                QueryCtrlType::Profile(format) => Ok(VideoCmdResponseType::Sync(
+                    CmdResponse::QueryControl(supported_profiles(format)),
+                )),
+                _ => Err(VideoError::InvalidArgument),
+            },
+            GetControl {
+                stream_id,
+                ctrl_type,
+            } => {
+                let stream = self.get_stream(stream_id)?;
+                let ctrl_val = match ctrl_type {
+                    CtrlType::BitrateMode => CtrlVal::BitrateMode(stream.rate_control.mode),
+                    CtrlType::Bitrate => CtrlVal::Bitrate(stream.rate_control.target_bitrate),
+                    CtrlType::BitratePeak => {
+                        CtrlVal::BitratePeak(stream.rate_control.peak_bitrate.unwrap_or(0))
+                    }
+                    CtrlType::Profile => CtrlVal::Profile(stream.profile),
+                    CtrlType::Level => match stream.level {
+                        Some(level) => CtrlVal::Level(level),
+                        None => return Err(VideoError::InvalidArgument),
+                    },
+                    _ => return Err(VideoError::InvalidArgument),
+                };
+                Ok(VideoCmdResponseType::Sync(CmdResponse::GetControl(ctrl_val)))
+            }
+            SetControl {
+                stream_id,
+                ctrl_val,
+            } => {
+                // The profile and level are negotiated through the control-set path rather than
+                // carried by `STREAM_CREATE`; applying either re-opens the LibVDA session so the
+                // guest's choice actually backs the encode instead of the coded format's default.
+                if let CtrlVal::Profile(profile) = ctrl_val {
+                    return self.set_profile(stream_id, profile, poll_ctx);
+                }
+                if let CtrlVal::Level(level) = ctrl_val {
+                    return self.set_level(stream_id, level, poll_ctx);
+                }
+                let stream = self.get_stream(stream_id)?;
+                match ctrl_val {
+                    CtrlVal::BitrateMode(mode) => stream.rate_control.mode = mode,
+                    CtrlVal::Bitrate(bitrate) => stream.rate_control.target_bitrate = bitrate,
+                    CtrlVal::BitratePeak(peak) => stream.rate_control.peak_bitrate = Some(peak),
+                    CtrlVal::Framerate(framerate) => stream.rate_control.framerate = framerate,
+                    _ => return Err(VideoError::InvalidArgument),
+                }
+                // Push the updated rate control down to the session so the change takes effect
+                // mid-stream.
+                stream
+                    .session
+                    .request_encoding_params_change(
+                        stream.rate_control.to_libvda_bitrate(),
+                        stream.rate_control.framerate,
+                    )
+                    .map_err(|e| {
+                        error!("failed to update encode params for stream {}: {}", stream_id, e);
+                        VideoError::InvalidOperation
+                    })?;
+                Ok(VideoCmdResponseType::Sync(CmdResponse::NoData))
+            }
+            _ => Err(VideoError::InvalidOperation),
+        }
+    }
+
+    fn process_event_fd(
+        &mut self,
+        stream_id: u32,
+        resource_bridge: &ResourceRequestSocket,
+    ) -> Option<Vec<VideoEvtResponseType>> {
+        let stream = match self.streams.get_mut(&stream_id) {
+            Some(s) => s,
+            None => {
+                error!("received event for unknown stream {}", stream_id);
+                return None;
+            }
+        };
+
+        let event = match stream.session.read_event() {
+            Ok(e) => e,
+            Err(e) => {
+                error!("failed to read event for stream {}: {}", stream_id, e);
+                return None;
+            }
+        };
+
+        use libvda::encode::Event::*;
+        let mut responses = Vec::new();
+        match event {
+            RequireInputBuffers {
+                input_count: _,
+                input_frame_width,
+                input_frame_height,
+                output_buffer_size,
+            } => {
+                stream.require_input_buffers_received = true;
+                stream.src_params.frame_width = input_frame_width;
+                stream.src_params.frame_height = input_frame_height;
+                stream.dst_params.plane_formats = vec![PlaneFormat {
+                    plane_size: output_buffer_size,
+                    stride: 0,
+                }];
+                // The guest queues its first input and output buffers before the backend is ready;
+                // now that `RequireInputBuffers` has arrived, drain those parked resources into the
+                // session so the first frame is not stuck waiting for a later `RESOURCE_QUEUE`.
+                if let Err(e) = try_submit_queued(stream, resource_bridge) {
+                    error!(
+                        "failed to submit queued buffers for stream {}: {}",
+                        stream_id, e
+                    );
+                    responses.push(VideoEvtResponseType::Event(VideoEvt {
+                        typ: EvtType::Error,
+                        stream_id,
+                    }));
+                }
+            }
+            ProcessedInputBuffer(input_buffer_id) => {
+                responses.push(VideoEvtResponseType::AsyncCmd {
+                    tag: AsyncCmdTag::Queue {
+                        stream_id,
+                        queue_type: QueueType::Input,
+                        resource_id: input_buffer_id,
+                    },
+                    resp: Ok(CmdResponse::ResourceQueue {
+                        timestamp: 0,
+                        flags: 0,
+                        size: 0,
+                    }),
+                });
+            }
+            BitstreamBufferReady {
+                output_buffer_id,
+                payload_size,
+                key_frame,
+                timestamp,
+            } => {
+                let flags = if key_frame {
+                    VIRTIO_VIDEO_BUFFER_FLAG_IFRAME
+                } else {
+                    0
+                };
+                responses.push(VideoEvtResponseType::AsyncCmd {
+                    tag: AsyncCmdTag::Queue {
+                        stream_id,
+                        queue_type: QueueType::Output,
+                        resource_id: output_buffer_id,
+                    },
+                    resp: Ok(CmdResponse::ResourceQueue {
+                        timestamp,
+                        flags,
+                        size: payload_size,
+                    }),
+                });
+            }
+            NotifyEndOfBitstreamBuffer(output_buffer_id) => {
+                // The backend is done with this output buffer; remember it so it can carry the EOS
+                // marker once the drain that is in flight finishes.
+                stream.eos_notification_buffer = Some(output_buffer_id);
+            }
+            FlushDone(success) => {
+                stream.draining = false;
+                let resp = if success {
+                    Ok(CmdResponse::NoData)
+                } else {
+                    Err(VideoError::InvalidOperation)
+                };
+                responses.push(VideoEvtResponseType::AsyncCmd {
+                    tag: AsyncCmdTag::Drain { stream_id },
+                    resp,
+                });
+            }
+            NotifyError(e) => {
+                error!("encode session {} reported error: {}", stream_id, e);
+                responses.push(VideoEvtResponseType::Event(VideoEvt {
+                    typ: EvtType::Error,
+                    stream_id,
+                }));
+            }
+        }
+        Some(responses)
+    }
+
+    fn take_resource_id_to_notify_eos(&mut self, stream_id: u32) -> Option<u32> {
+        self.streams
+            .get_mut(&stream_id)
+            .and_then(|s| s.eos_notification_buffer.take())
     }
+}
 
-    fn process_event_fd(&mut self, _stream_id: u32) -> Option<Vec<VideoEvtResponseType>> {
-        None
+/// Submits as many queued input/output resources to the session as it can accept, once
+/// `RequireInputBuffers` has been seen.
+fn try_submit_queued(
+    stream: &mut Stream,
+    resource_bridge: &ResourceRequestSocket,
+) -> VideoResult<()> {
+    if !stream.require_input_buffers_received {
+        return Ok(());
     }
 
-    fn take_resource_id_to_notify_eos(&mut self, _stream_id: u32) -> Option<u32> {
-        None
+    let outputs = std::mem::take(&mut stream.output_queue);
+    let output_result = submit_output_resources(stream, resource_bridge, outputs);
+
+    let inputs = std::mem::take(&mut stream.input_queue);
+    let input_result = submit_input_resources(stream, resource_bridge, inputs);
+
+    output_result.and(input_result)
+}
+
+/// Submits each queued output resource to `stream`'s session in order. A resource that can't be
+/// submitted yet, and everything still queued behind it, is pushed back onto
+/// `stream.output_queue` instead of being dropped, so it's retried on the next call rather than
+/// leaving the guest's `RESOURCE_QUEUE` for it pending forever.
+fn submit_output_resources(
+    stream: &mut Stream,
+    resource_bridge: &ResourceRequestSocket,
+    outputs: Vec<OutputResource>,
+) -> VideoResult<()> {
+    let mut outputs = outputs.into_iter();
+    while let Some(output) = outputs.next() {
+        let result = get_resource_info(resource_bridge, output.resource_id).and_then(|info| {
+            stream
+                .session
+                .use_output_buffer(output.resource_id, info.file)
+                .map_err(|e| {
+                    error!("use_output_buffer failed: {}", e);
+                    VideoError::InvalidOperation
+                })
+        });
+        if let Err(e) = result {
+            stream.output_queue.push(output);
+            stream.output_queue.extend(outputs);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Submits each queued input resource to `stream`'s session in order, with the same
+/// push-back-the-remainder behavior as [`submit_output_resources`] on a mid-loop failure.
+fn submit_input_resources(
+    stream: &mut Stream,
+    resource_bridge: &ResourceRequestSocket,
+    inputs: Vec<InputResource>,
+) -> VideoResult<()> {
+    let force_keyframe = false;
+    let mut inputs = inputs.into_iter();
+    while let Some(input) = inputs.next() {
+        let result = get_resource_info(resource_bridge, input.resource_id).and_then(|info| {
+            stream
+                .session
+                .encode(
+                    input.resource_id,
+                    info.file,
+                    &input.planes,
+                    input.timestamp,
+                    force_keyframe,
+                )
+                .map_err(|e| {
+                    error!("encode failed: {}", e);
+                    VideoError::InvalidOperation
+                })
+        });
+        if let Err(e) = result {
+            stream.input_queue.push(input);
+            stream.input_queue.extend(inputs);
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+fn get_resource_info(
+    resource_bridge: &ResourceRequestSocket,
+    resource_id: u32,
+) -> VideoResult<ResourceInfo> {
+    resource_bridge::get_resource_info(resource_bridge, resource_id)
+        .map_err(VideoError::ResourceBridgeFailure)
+}
+
+fn default_profile_for(format: Format) -> Option<Profile> {
+    match format {
+        Format::H264 => Some(Profile::H264Baseline),
+        Format::HEVC => Some(Profile::HevcMain),
+        Format::VP8 => Some(Profile::VP8Profile0),
+        Format::VP9 => Some(Profile::VP9Profile0),
+        _ => None,
+    }
+}
+
+fn supported_profiles(format: Format) -> Vec<Profile> {
+    use Profile::*;
+    match format {
+        Format::H264 => vec![H264Baseline, H264Main, H264High],
+        Format::HEVC => vec![HevcMain],
+        Format::VP8 => vec![VP8Profile0],
+        Format::VP9 => vec![VP9Profile0],
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libvda::encode::OutputFormat;
+
+    #[test]
+    fn from_libvda_merges_profiles_for_the_same_coded_format() {
+        let caps = EncodeCapabilities {
+            input_formats: vec![libvda::PixelFormat::NV12],
+            output_formats: vec![
+                OutputFormat {
+                    profile: libvda::Profile::H264ProfileBaseline,
+                    max_width: 1920,
+                    max_height: 1080,
+                    max_bitrate: 20_000_000,
+                },
+                OutputFormat {
+                    profile: libvda::Profile::H264ProfileHigh,
+                    max_width: 3840,
+                    max_height: 2160,
+                    max_bitrate: 40_000_000,
+                },
+            ],
+        };
+
+        let encoder_caps = EncoderCapabilities::from_libvda(&caps);
+
+        assert_eq!(encoder_caps.input_format_descs.len(), 1);
+        assert_eq!(encoder_caps.input_format_descs[0].format, Format::NV12);
+
+        // Both H.264 profiles fold into the single H264 FormatDesc, with the frame size and
+        // bitrate envelope widened to the larger of the two profiles' limits.
+        assert_eq!(encoder_caps.output_format_descs.len(), 1);
+        let h264 = &encoder_caps.output_format_descs[0];
+        assert_eq!(h264.format, Format::H264);
+        let frame_format = &h264.frame_formats[0];
+        assert_eq!(frame_format.width.max, 3840);
+        assert_eq!(frame_format.height.max, 2160);
+        assert_eq!(frame_format.bitrates[0].max, 40_000_000);
+    }
+
+    #[test]
+    fn from_libvda_maps_a_vp9_profile_to_the_vp9_format() {
+        let caps = EncodeCapabilities {
+            input_formats: vec![],
+            output_formats: vec![OutputFormat {
+                profile: libvda::Profile::VP9Profile0,
+                max_width: 1280,
+                max_height: 720,
+                max_bitrate: 10_000_000,
+            }],
+        };
+
+        let encoder_caps = EncoderCapabilities::from_libvda(&caps);
+        assert_eq!(encoder_caps.output_format_descs.len(), 1);
+        assert_eq!(encoder_caps.output_format_descs[0].format, Format::VP9);
     }
 }
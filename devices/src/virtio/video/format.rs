@@ -53,8 +53,6 @@ macro_rules! impl_libvda_conversion {
             }
         }
 
-        // TODO(alexlau): Remove this after encoder CL lands.
-        #[allow(dead_code)]
         pub fn to_libvda_profile(&self) -> Option<libvda::Profile> {
             match self {
                 $(Self::$y => Some(libvda::Profile::$x),)*
@@ -85,6 +83,59 @@ impl Profile {
         }
     }
 
+    /// The levels that may be negotiated for this profile, in ascending order. Used both to
+    /// validate a guest-requested profile+level pair and to report the maximum supported level in
+    /// capability queries.
+    pub fn supported_levels(&self) -> Vec<Level> {
+        use Level::*;
+        use Profile::*;
+        match self {
+            H264Baseline
+            | H264Main
+            | H264Extended
+            | H264High
+            | H264High10
+            | H264High422
+            | H264High444PredictiveProfile
+            | H264ScalableBaseline
+            | H264ScalableHigh
+            | H264StereoHigh
+            | H264MultiviewHigh => vec![
+                H264_1_0, H264_1_1, H264_1_2, H264_1_3, H264_2_0, H264_2_1, H264_2_2, H264_3_0,
+                H264_3_1, H264_3_2, H264_4_0, H264_4_1, H264_4_2, H264_5_0, H264_5_1, H264_5_2,
+                H264_6_0, H264_6_1, H264_6_2,
+            ],
+            HevcMain | HevcMain10 | HevcMainStillPicture => vec![
+                HevcMain1_0,
+                HevcMain2_0,
+                HevcMain2_1,
+                HevcMain3_0,
+                HevcMain3_1,
+                HevcMain4_0,
+                HevcMain4_1,
+                HevcMain5_0,
+                HevcMain5_1,
+                HevcMain5_2,
+                HevcMain6_0,
+                HevcMain6_1,
+                HevcMain6_2,
+                HevcHigh4_0,
+                HevcHigh4_1,
+                HevcHigh5_0,
+                HevcHigh5_1,
+                HevcHigh5_2,
+                HevcHigh6_0,
+                HevcHigh6_1,
+                HevcHigh6_2,
+            ],
+            VP8Profile0 | VP8Profile1 | VP8Profile2 | VP8Profile3 => vec![],
+            VP9Profile0 | VP9Profile1 | VP9Profile2 | VP9Profile3 => vec![
+                VP9_1_0, VP9_1_1, VP9_2_0, VP9_2_1, VP9_3_0, VP9_3_1, VP9_4_0, VP9_4_1, VP9_5_0,
+                VP9_5_1, VP9_5_2, VP9_6_0, VP9_6_1, VP9_6_2,
+            ],
+        }
+    }
+
     impl_libvda_conversion!(
         (H264ProfileBaseline, H264Baseline),
         (H264ProfileMain, H264Main),
@@ -114,10 +165,142 @@ impl Profile {
 #[derive(PartialEq, Eq, PartialOrd, Ord, N, Clone, Copy, Debug)]
 #[repr(u32)]
 pub enum Level {
+    // H.264 level ladder (Annex A).
     H264_1_0 = VIRTIO_VIDEO_LEVEL_H264_1_0,
+    H264_1_1 = VIRTIO_VIDEO_LEVEL_H264_1_1,
+    H264_1_2 = VIRTIO_VIDEO_LEVEL_H264_1_2,
+    H264_1_3 = VIRTIO_VIDEO_LEVEL_H264_1_3,
+    H264_2_0 = VIRTIO_VIDEO_LEVEL_H264_2_0,
+    H264_2_1 = VIRTIO_VIDEO_LEVEL_H264_2_1,
+    H264_2_2 = VIRTIO_VIDEO_LEVEL_H264_2_2,
+    H264_3_0 = VIRTIO_VIDEO_LEVEL_H264_3_0,
+    H264_3_1 = VIRTIO_VIDEO_LEVEL_H264_3_1,
+    H264_3_2 = VIRTIO_VIDEO_LEVEL_H264_3_2,
+    H264_4_0 = VIRTIO_VIDEO_LEVEL_H264_4_0,
+    H264_4_1 = VIRTIO_VIDEO_LEVEL_H264_4_1,
+    H264_4_2 = VIRTIO_VIDEO_LEVEL_H264_4_2,
+    H264_5_0 = VIRTIO_VIDEO_LEVEL_H264_5_0,
+    H264_5_1 = VIRTIO_VIDEO_LEVEL_H264_5_1,
+    H264_5_2 = VIRTIO_VIDEO_LEVEL_H264_5_2,
+    H264_6_0 = VIRTIO_VIDEO_LEVEL_H264_6_0,
+    H264_6_1 = VIRTIO_VIDEO_LEVEL_H264_6_1,
+    H264_6_2 = VIRTIO_VIDEO_LEVEL_H264_6_2,
+
+    // HEVC level set, split across the Main and High tiers.
+    HevcMain1_0 = VIRTIO_VIDEO_LEVEL_HEVC_MAIN_1_0,
+    HevcMain2_0 = VIRTIO_VIDEO_LEVEL_HEVC_MAIN_2_0,
+    HevcMain2_1 = VIRTIO_VIDEO_LEVEL_HEVC_MAIN_2_1,
+    HevcMain3_0 = VIRTIO_VIDEO_LEVEL_HEVC_MAIN_3_0,
+    HevcMain3_1 = VIRTIO_VIDEO_LEVEL_HEVC_MAIN_3_1,
+    HevcMain4_0 = VIRTIO_VIDEO_LEVEL_HEVC_MAIN_4_0,
+    HevcMain4_1 = VIRTIO_VIDEO_LEVEL_HEVC_MAIN_4_1,
+    HevcMain5_0 = VIRTIO_VIDEO_LEVEL_HEVC_MAIN_5_0,
+    HevcMain5_1 = VIRTIO_VIDEO_LEVEL_HEVC_MAIN_5_1,
+    HevcMain5_2 = VIRTIO_VIDEO_LEVEL_HEVC_MAIN_5_2,
+    HevcMain6_0 = VIRTIO_VIDEO_LEVEL_HEVC_MAIN_6_0,
+    HevcMain6_1 = VIRTIO_VIDEO_LEVEL_HEVC_MAIN_6_1,
+    HevcMain6_2 = VIRTIO_VIDEO_LEVEL_HEVC_MAIN_6_2,
+    HevcHigh4_0 = VIRTIO_VIDEO_LEVEL_HEVC_HIGH_4_0,
+    HevcHigh4_1 = VIRTIO_VIDEO_LEVEL_HEVC_HIGH_4_1,
+    HevcHigh5_0 = VIRTIO_VIDEO_LEVEL_HEVC_HIGH_5_0,
+    HevcHigh5_1 = VIRTIO_VIDEO_LEVEL_HEVC_HIGH_5_1,
+    HevcHigh5_2 = VIRTIO_VIDEO_LEVEL_HEVC_HIGH_5_2,
+    HevcHigh6_0 = VIRTIO_VIDEO_LEVEL_HEVC_HIGH_6_0,
+    HevcHigh6_1 = VIRTIO_VIDEO_LEVEL_HEVC_HIGH_6_1,
+    HevcHigh6_2 = VIRTIO_VIDEO_LEVEL_HEVC_HIGH_6_2,
+
+    // VP9 level ladder.
+    VP9_1_0 = VIRTIO_VIDEO_LEVEL_VP9_1_0,
+    VP9_1_1 = VIRTIO_VIDEO_LEVEL_VP9_1_1,
+    VP9_2_0 = VIRTIO_VIDEO_LEVEL_VP9_2_0,
+    VP9_2_1 = VIRTIO_VIDEO_LEVEL_VP9_2_1,
+    VP9_3_0 = VIRTIO_VIDEO_LEVEL_VP9_3_0,
+    VP9_3_1 = VIRTIO_VIDEO_LEVEL_VP9_3_1,
+    VP9_4_0 = VIRTIO_VIDEO_LEVEL_VP9_4_0,
+    VP9_4_1 = VIRTIO_VIDEO_LEVEL_VP9_4_1,
+    VP9_5_0 = VIRTIO_VIDEO_LEVEL_VP9_5_0,
+    VP9_5_1 = VIRTIO_VIDEO_LEVEL_VP9_5_1,
+    VP9_5_2 = VIRTIO_VIDEO_LEVEL_VP9_5_2,
+    VP9_6_0 = VIRTIO_VIDEO_LEVEL_VP9_6_0,
+    VP9_6_1 = VIRTIO_VIDEO_LEVEL_VP9_6_1,
+    VP9_6_2 = VIRTIO_VIDEO_LEVEL_VP9_6_2,
 }
 impl_try_from_le32_for_enumn!(Level, "level");
 
+impl Level {
+    /// Encodes an H.264 level as LibVDA's `level * 10` representation (e.g. level 4.1 becomes 41).
+    /// `None` for non-H.264 levels, which LibVDA's `h264_output_level` has no use for.
+    pub fn to_libvda_h264_level(&self) -> Option<u32> {
+        use Level::*;
+        let (major, minor) = match self {
+            H264_1_0 => (1, 0),
+            H264_1_1 => (1, 1),
+            H264_1_2 => (1, 2),
+            H264_1_3 => (1, 3),
+            H264_2_0 => (2, 0),
+            H264_2_1 => (2, 1),
+            H264_2_2 => (2, 2),
+            H264_3_0 => (3, 0),
+            H264_3_1 => (3, 1),
+            H264_3_2 => (3, 2),
+            H264_4_0 => (4, 0),
+            H264_4_1 => (4, 1),
+            H264_4_2 => (4, 2),
+            H264_5_0 => (5, 0),
+            H264_5_1 => (5, 1),
+            H264_5_2 => (5, 2),
+            H264_6_0 => (6, 0),
+            H264_6_1 => (6, 1),
+            H264_6_2 => (6, 2),
+            _ => return None,
+        };
+        Some(major * 10 + minor)
+    }
+}
+
+/// The rate-control mode a hardware encoder operates in. Mirrors V4L2's
+/// `V4L2_CID_MPEG_VIDEO_BITRATE_MODE` menu control.
+#[derive(PartialEq, Eq, PartialOrd, Ord, N, Clone, Copy, Debug)]
+#[repr(u32)]
+pub enum BitrateMode {
+    Cbr = VIRTIO_VIDEO_BITRATE_MODE_CBR,
+    Vbr = VIRTIO_VIDEO_BITRATE_MODE_VBR,
+}
+impl_try_from_le32_for_enumn!(BitrateMode, "bitrate mode");
+
+impl Default for BitrateMode {
+    fn default() -> Self {
+        BitrateMode::Cbr
+    }
+}
+
+/// A rate-control request from the guest: the target (and, for VBR, peak) bitrate, and the
+/// framerate the bitrate is budgeted against.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RateControl {
+    pub mode: BitrateMode,
+    pub target_bitrate: u32,
+    // Only meaningful when `mode` is `Vbr`; ignored otherwise.
+    pub peak_bitrate: Option<u32>,
+    pub framerate: u32,
+}
+
+impl RateControl {
+    /// Translates the rate control into LibVDA's encode bitrate representation.
+    pub fn to_libvda_bitrate(&self) -> libvda::encode::Bitrate {
+        let mode = match self.mode {
+            BitrateMode::Cbr => libvda::encode::BitrateMode::CBR,
+            BitrateMode::Vbr => libvda::encode::BitrateMode::VBR,
+        };
+        libvda::encode::Bitrate {
+            mode,
+            target: self.target_bitrate,
+            // LibVDA only consults the peak for VBR; fall back to the target for CBR.
+            peak: self.peak_bitrate.unwrap_or(self.target_bitrate),
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, PartialOrd, Ord, N, Clone, Copy, Debug)]
 #[repr(u32)]
 pub enum Format {
@@ -162,6 +345,12 @@ pub struct FrameFormat {
     pub width: FormatRange,
     pub height: FormatRange,
     pub bitrates: Vec<FormatRange>,
+    // The rate-control modes the encoder can apply to this coded format. Empty for raw formats and
+    // for decoders, which have no rate control to advertise.
+    pub bitrate_modes: Vec<BitrateMode>,
+    // The highest profile/level pair the backend supports for this coded format, reported so the
+    // guest can bound its level negotiation. `None` for formats without level semantics (VP8, raw).
+    pub max_level: Option<Level>,
 }
 
 impl Response for FrameFormat {
@@ -170,13 +359,16 @@ impl Response for FrameFormat {
             width: self.width.into(),
             height: self.height.into(),
             num_rates: Le32::from(self.bitrates.len() as u32),
+            num_bitrate_modes: Le32::from(self.bitrate_modes.len() as u32),
+            max_level: Le32::from(self.max_level.map_or(0, |l| l as u32)),
             ..Default::default()
         })?;
         w.write_iter(
             self.bitrates
                 .iter()
                 .map(|r| Into::<virtio_video_format_range>::into(*r)),
-        )
+        )?;
+        w.write_iter(self.bitrate_modes.iter().map(|m| Le32::from(*m as u32)))
     }
 }
 
@@ -201,3 +393,40 @@ impl Response for FormatDesc {
         self.frame_formats.iter().map(|ff| ff.write(w)).collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supported_levels_orders_the_h264_ladder_and_bounds_it_to_6_2() {
+        let levels = Profile::H264High.supported_levels();
+        assert_eq!(levels.first(), Some(&Level::H264_1_0));
+        assert_eq!(levels.last(), Some(&Level::H264_6_2));
+        assert!(levels.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn supported_levels_is_empty_for_vp8() {
+        assert!(Profile::VP8Profile0.supported_levels().is_empty());
+    }
+
+    #[test]
+    fn supported_levels_splits_hevc_into_main_and_high_tiers() {
+        let levels = Profile::HevcMain.supported_levels();
+        assert_eq!(levels.first(), Some(&Level::HevcMain1_0));
+        assert_eq!(levels.last(), Some(&Level::HevcHigh6_2));
+    }
+
+    #[test]
+    fn to_libvda_h264_level_encodes_major_and_minor() {
+        assert_eq!(Level::H264_4_1.to_libvda_h264_level(), Some(41));
+        assert_eq!(Level::H264_1_0.to_libvda_h264_level(), Some(10));
+    }
+
+    #[test]
+    fn to_libvda_h264_level_is_none_for_non_h264_levels() {
+        assert_eq!(Level::HevcMain1_0.to_libvda_h264_level(), None);
+        assert_eq!(Level::VP9_1_0.to_libvda_h264_level(), None);
+    }
+}
@@ -0,0 +1,187 @@
+// Copyright 2020 The Chromium OS Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! This module defines the virtio video protocol constants and wire structs shared with the guest.
+//! The layout mirrors the `virtio_video.h` UAPI header; the structs are plain `repr(C)` records so
+//! they can be read from and written to the virtqueues directly.
+
+#![allow(dead_code)]
+#![allow(non_camel_case_types)]
+
+use data_model::{DataInit, Le32, Le64};
+
+//
+// Raw and coded pixel formats (`enum virtio_video_format`).
+//
+pub const VIRTIO_VIDEO_FORMAT_NV12: u32 = 3;
+pub const VIRTIO_VIDEO_FORMAT_YUV420: u32 = 4;
+pub const VIRTIO_VIDEO_FORMAT_H264: u32 = 0x1002;
+pub const VIRTIO_VIDEO_FORMAT_HEVC: u32 = 0x1003;
+pub const VIRTIO_VIDEO_FORMAT_VP8: u32 = 0x1004;
+pub const VIRTIO_VIDEO_FORMAT_VP9: u32 = 0x1005;
+
+//
+// Coded profiles (`enum virtio_video_profile`).
+//
+pub const VIRTIO_VIDEO_PROFILE_H264_BASELINE: u32 = 0x100;
+pub const VIRTIO_VIDEO_PROFILE_H264_MAIN: u32 = 0x101;
+pub const VIRTIO_VIDEO_PROFILE_H264_EXTENDED: u32 = 0x102;
+pub const VIRTIO_VIDEO_PROFILE_H264_HIGH: u32 = 0x103;
+pub const VIRTIO_VIDEO_PROFILE_H264_HIGH10PROFILE: u32 = 0x104;
+pub const VIRTIO_VIDEO_PROFILE_H264_HIGH422PROFILE: u32 = 0x105;
+pub const VIRTIO_VIDEO_PROFILE_H264_HIGH444PREDICTIVEPROFILE: u32 = 0x106;
+pub const VIRTIO_VIDEO_PROFILE_H264_SCALABLEBASELINE: u32 = 0x107;
+pub const VIRTIO_VIDEO_PROFILE_H264_SCALABLEHIGH: u32 = 0x108;
+pub const VIRTIO_VIDEO_PROFILE_H264_STEREOHIGH: u32 = 0x109;
+pub const VIRTIO_VIDEO_PROFILE_H264_MULTIVIEWHIGH: u32 = 0x10A;
+pub const VIRTIO_VIDEO_PROFILE_HEVC_MAIN: u32 = 0x200;
+pub const VIRTIO_VIDEO_PROFILE_HEVC_MAIN10: u32 = 0x201;
+pub const VIRTIO_VIDEO_PROFILE_HEVC_MAIN_STILL_PICTURE: u32 = 0x202;
+pub const VIRTIO_VIDEO_PROFILE_VP8_PROFILE0: u32 = 0x300;
+pub const VIRTIO_VIDEO_PROFILE_VP8_PROFILE1: u32 = 0x301;
+pub const VIRTIO_VIDEO_PROFILE_VP8_PROFILE2: u32 = 0x302;
+pub const VIRTIO_VIDEO_PROFILE_VP8_PROFILE3: u32 = 0x303;
+pub const VIRTIO_VIDEO_PROFILE_VP9_PROFILE0: u32 = 0x400;
+pub const VIRTIO_VIDEO_PROFILE_VP9_PROFILE1: u32 = 0x401;
+pub const VIRTIO_VIDEO_PROFILE_VP9_PROFILE2: u32 = 0x402;
+pub const VIRTIO_VIDEO_PROFILE_VP9_PROFILE3: u32 = 0x403;
+
+//
+// Coded levels (`enum virtio_video_level`). The H.264 ladder is based at 0x100, HEVC (Main and
+// High tiers) at 0x200, and VP9 at 0x300. The H.264 and HEVC bases match those of the profile
+// constants; the VP9 base does not, as VP9 profiles start at 0x400.
+//
+pub const VIRTIO_VIDEO_LEVEL_H264_1_0: u32 = 0x100;
+pub const VIRTIO_VIDEO_LEVEL_H264_1_1: u32 = 0x101;
+pub const VIRTIO_VIDEO_LEVEL_H264_1_2: u32 = 0x102;
+pub const VIRTIO_VIDEO_LEVEL_H264_1_3: u32 = 0x103;
+pub const VIRTIO_VIDEO_LEVEL_H264_2_0: u32 = 0x104;
+pub const VIRTIO_VIDEO_LEVEL_H264_2_1: u32 = 0x105;
+pub const VIRTIO_VIDEO_LEVEL_H264_2_2: u32 = 0x106;
+pub const VIRTIO_VIDEO_LEVEL_H264_3_0: u32 = 0x107;
+pub const VIRTIO_VIDEO_LEVEL_H264_3_1: u32 = 0x108;
+pub const VIRTIO_VIDEO_LEVEL_H264_3_2: u32 = 0x109;
+pub const VIRTIO_VIDEO_LEVEL_H264_4_0: u32 = 0x10A;
+pub const VIRTIO_VIDEO_LEVEL_H264_4_1: u32 = 0x10B;
+pub const VIRTIO_VIDEO_LEVEL_H264_4_2: u32 = 0x10C;
+pub const VIRTIO_VIDEO_LEVEL_H264_5_0: u32 = 0x10D;
+pub const VIRTIO_VIDEO_LEVEL_H264_5_1: u32 = 0x10E;
+pub const VIRTIO_VIDEO_LEVEL_H264_5_2: u32 = 0x10F;
+pub const VIRTIO_VIDEO_LEVEL_H264_6_0: u32 = 0x110;
+pub const VIRTIO_VIDEO_LEVEL_H264_6_1: u32 = 0x111;
+pub const VIRTIO_VIDEO_LEVEL_H264_6_2: u32 = 0x112;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_MAIN_1_0: u32 = 0x200;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_MAIN_2_0: u32 = 0x201;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_MAIN_2_1: u32 = 0x202;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_MAIN_3_0: u32 = 0x203;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_MAIN_3_1: u32 = 0x204;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_MAIN_4_0: u32 = 0x205;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_MAIN_4_1: u32 = 0x206;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_MAIN_5_0: u32 = 0x207;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_MAIN_5_1: u32 = 0x208;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_MAIN_5_2: u32 = 0x209;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_MAIN_6_0: u32 = 0x20A;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_MAIN_6_1: u32 = 0x20B;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_MAIN_6_2: u32 = 0x20C;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_HIGH_4_0: u32 = 0x20D;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_HIGH_4_1: u32 = 0x20E;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_HIGH_5_0: u32 = 0x20F;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_HIGH_5_1: u32 = 0x210;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_HIGH_5_2: u32 = 0x211;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_HIGH_6_0: u32 = 0x212;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_HIGH_6_1: u32 = 0x213;
+pub const VIRTIO_VIDEO_LEVEL_HEVC_HIGH_6_2: u32 = 0x214;
+pub const VIRTIO_VIDEO_LEVEL_VP9_1_0: u32 = 0x300;
+pub const VIRTIO_VIDEO_LEVEL_VP9_1_1: u32 = 0x301;
+pub const VIRTIO_VIDEO_LEVEL_VP9_2_0: u32 = 0x302;
+pub const VIRTIO_VIDEO_LEVEL_VP9_2_1: u32 = 0x303;
+pub const VIRTIO_VIDEO_LEVEL_VP9_3_0: u32 = 0x304;
+pub const VIRTIO_VIDEO_LEVEL_VP9_3_1: u32 = 0x305;
+pub const VIRTIO_VIDEO_LEVEL_VP9_4_0: u32 = 0x306;
+pub const VIRTIO_VIDEO_LEVEL_VP9_4_1: u32 = 0x307;
+pub const VIRTIO_VIDEO_LEVEL_VP9_5_0: u32 = 0x308;
+pub const VIRTIO_VIDEO_LEVEL_VP9_5_1: u32 = 0x309;
+pub const VIRTIO_VIDEO_LEVEL_VP9_5_2: u32 = 0x30A;
+pub const VIRTIO_VIDEO_LEVEL_VP9_6_0: u32 = 0x30B;
+pub const VIRTIO_VIDEO_LEVEL_VP9_6_1: u32 = 0x30C;
+pub const VIRTIO_VIDEO_LEVEL_VP9_6_2: u32 = 0x30D;
+
+//
+// Rate-control modes (`enum virtio_video_bitrate_mode`). Mirrors V4L2's bitrate-mode menu control.
+//
+pub const VIRTIO_VIDEO_BITRATE_MODE_VBR: u32 = 0;
+pub const VIRTIO_VIDEO_BITRATE_MODE_CBR: u32 = 1;
+
+//
+// Resource plane layout (`enum virtio_video_planes_layout_flag`).
+//
+pub const VIRTIO_VIDEO_PLANES_LAYOUT_SINGLE_BUFFER: u32 = 1 << 0;
+pub const VIRTIO_VIDEO_PLANES_LAYOUT_PER_PLANE: u32 = 1 << 1;
+
+//
+// Buffer flags reported on a dequeued resource (`VIRTIO_VIDEO_BUFFER_FLAG_*`).
+//
+pub const VIRTIO_VIDEO_BUFFER_FLAG_ERR: u32 = 0x0001;
+pub const VIRTIO_VIDEO_BUFFER_FLAG_EOS: u32 = 0x0002;
+pub const VIRTIO_VIDEO_BUFFER_FLAG_IFRAME: u32 = 0x0004;
+pub const VIRTIO_VIDEO_BUFFER_FLAG_PFRAME: u32 = 0x0008;
+pub const VIRTIO_VIDEO_BUFFER_FLAG_BFRAME: u32 = 0x0010;
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct virtio_video_crop {
+    pub left: Le32,
+    pub top: Le32,
+    pub width: Le32,
+    pub height: Le32,
+}
+// Safe because it only has data and has no implicit padding.
+unsafe impl DataInit for virtio_video_crop {}
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct virtio_video_plane_format {
+    pub plane_size: Le32,
+    pub stride: Le32,
+}
+// Safe because it only has data and has no implicit padding.
+unsafe impl DataInit for virtio_video_plane_format {}
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct virtio_video_format_range {
+    pub min: Le32,
+    pub max: Le32,
+    pub step: Le32,
+}
+// Safe because it only has data and has no implicit padding.
+unsafe impl DataInit for virtio_video_format_range {}
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct virtio_video_format_frame {
+    pub width: virtio_video_format_range,
+    pub height: virtio_video_format_range,
+    pub num_rates: Le32,
+    // Number of rate-control modes, one `Le32` each, written after the `num_rates`
+    // `virtio_video_format_range` bitrate entries that follow this struct.
+    pub num_bitrate_modes: Le32,
+    // The highest profile level the backend supports for this coded format, or 0 when the format
+    // has no level semantics.
+    pub max_level: Le32,
+}
+// Safe because it only has data and has no implicit padding.
+unsafe impl DataInit for virtio_video_format_frame {}
+
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+pub struct virtio_video_format_desc {
+    pub mask: Le64,
+    pub format: Le32,
+    pub planes_layout: Le32,
+    pub plane_align: Le32,
+    pub num_frames: Le32,
+}
+// Safe because it only has data and has no implicit padding.
+unsafe impl DataInit for virtio_video_format_desc {}
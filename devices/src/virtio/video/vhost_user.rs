@@ -0,0 +1,300 @@
+// Copyright 2020 The Chromium OS Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style license that can be
+// found in the LICENSE file.
+
+//! vhost-user transport for the virtio-video device.
+//!
+//! The in-VMM [`Device`] implementations (the decoder and the [`Encoder`]) talk to LibVDA
+//! directly, which means the large, closed accelerated-codec library is linked into the monitor
+//! process. To let that code run in a separate, tightly sandboxed helper, this module factors the
+//! `process_cmd`/`process_event_fd` datapath defined by the `Device` trait across a process
+//! boundary: crosvm acts as the vhost-user frontend ([`VideoFrontend`], itself a [`Device`]) while a
+//! standalone helper process runs [`VideoBackend`], which owns the LibVDA sessions and answers the
+//! decoded commands the frontend forwards over the socket. The virtqueues themselves stay with
+//! crosvm's virtio-video worker: only decoded commands, their responses and the device's events
+//! cross the process boundary.
+//!
+//! This mirrors the vhost-device/virtio-loopback split used for the CAN, GPIO and RNG device
+//! models, where the device model lives in its own vhost-user backend and the VMM only proxies the
+//! virtqueues. Packaging [`run_backend`] as a standalone `vhost-user-video` binary and wiring
+//! crosvm's device selection to construct a [`VideoFrontend`] instead of an in-process `Encoder` or
+//! decoder are not done here.
+//!
+//! [`Device`]: crate::virtio::video::device::Device
+//! [`Encoder`]: crate::virtio::video::encoder::Encoder
+
+use std::collections::VecDeque;
+
+use msg_socket::{MsgOnSocket, MsgReceiver, MsgSender, MsgSocket};
+use sys_util::{error, PollContext};
+
+use crate::virtio::resource_bridge::ResourceRequestSocket;
+use crate::virtio::video::command::VideoCmd;
+use crate::virtio::video::device::{Device, Token, VideoCmdResponseType, VideoEvtResponseType};
+use crate::virtio::video::error::{VideoError, VideoResult};
+
+/// A request sent from the frontend to the backend: a decoded guest command to run against the
+/// backend's `Device`, or a query against its state. No file descriptors travel with a request:
+/// the backend resolves the resources a command refers to through its own resource-bridge socket.
+#[derive(MsgOnSocket)]
+pub enum VideoRequest {
+    /// Run `process_cmd` for a decoded guest command.
+    ProcessCmd(VideoCmd),
+    /// Run `take_resource_id_to_notify_eos` for the given stream.
+    TakeEosNotificationBuffer(u32),
+    /// Tear the backend down.
+    Stop,
+}
+
+/// A reply sent from the backend to the frontend: the response to a `ProcessCmd` or
+/// `TakeEosNotificationBuffer` request, or an asynchronous event produced by the backend's event
+/// loop.
+#[derive(MsgOnSocket)]
+pub enum VideoResponse {
+    /// The response produced by the backend's `process_cmd`.
+    CmdResult(VideoResult<VideoCmdResponseType>),
+    /// The response produced by the backend's `take_resource_id_to_notify_eos`.
+    EosNotificationBuffer(Option<u32>),
+    /// An asynchronous event produced by the backend's `process_event_fd`.
+    Event(VideoEvtResponseType),
+}
+
+/// The serialization seam the vhost-user transport has to preserve so the command queue and event
+/// queue survive the process boundary. Resource-bridge descriptors are not passed across it.
+///
+/// The frontend serializes a decoded [`VideoCmd`] onto the socket; the backend deserializes it, runs the real `Device` datapath against its LibVDA
+/// sessions, and serializes the [`VideoCmdResponseType`]/[`VideoEvtResponseType`] back.
+pub trait VideoProtocol {
+    /// Serializes a request onto the vhost-user socket.
+    fn send_request(&self, req: VideoRequest) -> VideoResult<()>;
+
+    /// Receives the next response or asynchronous event produced by the backend.
+    fn recv_response(&self) -> VideoResult<VideoResponse>;
+}
+
+/// The crosvm-side proxy. It owns the vhost-user socket to the backend and forwards the datapath
+/// the virtio-video worker drives; it performs no LibVDA work itself.
+pub struct VideoFrontend {
+    sock: MsgSocket<VideoRequest, VideoResponse>,
+    // `Event`s the backend pushed in while we were blocked waiting on the reply to an in-flight
+    // `ProcessCmd`/`TakeEosNotificationBuffer` request. `VideoBackend::run` services its command
+    // queue and every session's event FD from the same poll loop, so an `Event` can legitimately
+    // land on the socket ahead of the reply to a request already sent -- stash it here instead of
+    // mistaking it for that reply, and hand it to the next `process_event_fd` call instead.
+    pending_events: VecDeque<VideoEvtResponseType>,
+}
+
+impl VideoFrontend {
+    pub fn new(sock: MsgSocket<VideoRequest, VideoResponse>) -> Self {
+        VideoFrontend {
+            sock,
+            pending_events: VecDeque::new(),
+        }
+    }
+
+    /// Waits for the reply to a request already sent, stashing any `Event`s that arrive ahead of
+    /// it so `process_event_fd` can still deliver them.
+    fn recv_sync_response(&mut self) -> VideoResult<VideoResponse> {
+        loop {
+            match self.recv_response()? {
+                VideoResponse::Event(event) => self.pending_events.push_back(event),
+                other => return Ok(other),
+            }
+        }
+    }
+}
+
+impl VideoProtocol for VideoFrontend {
+    fn send_request(&self, req: VideoRequest) -> VideoResult<()> {
+        self.sock.send(&req).map_err(|e| {
+            error!("failed to forward video request to backend: {}", e);
+            VideoError::InvalidOperation
+        })
+    }
+
+    fn recv_response(&self) -> VideoResult<VideoResponse> {
+        self.sock.recv().map_err(|e| {
+            error!("failed to read video response from backend: {}", e);
+            VideoError::InvalidOperation
+        })
+    }
+}
+
+impl Device for VideoFrontend {
+    fn process_cmd(
+        &mut self,
+        cmd: VideoCmd,
+        _poll_ctx: &PollContext<Token>,
+        _resource_bridge: &ResourceRequestSocket,
+    ) -> VideoResult<VideoCmdResponseType> {
+        // The backend owns the real poll context and resource bridge; this side only forwards the
+        // decoded command and relays back whatever it answers with.
+        self.send_request(VideoRequest::ProcessCmd(cmd))?;
+        match self.recv_sync_response()? {
+            VideoResponse::CmdResult(result) => result,
+            _ => {
+                error!("backend sent an unexpected response to ProcessCmd");
+                Err(VideoError::InvalidOperation)
+            }
+        }
+    }
+
+    fn process_event_fd(
+        &mut self,
+        _stream_id: u32,
+        _resource_bridge: &ResourceRequestSocket,
+    ) -> Option<Vec<VideoEvtResponseType>> {
+        // An event may already be stashed if it arrived while we were blocked on a command reply;
+        // only fall back to the socket once that backlog is drained.
+        if let Some(event) = self.pending_events.pop_front() {
+            return Some(vec![event]);
+        }
+        match self.recv_response() {
+            Ok(VideoResponse::Event(event)) => Some(vec![event]),
+            Ok(_) => {
+                error!("backend sent an unexpected response while polling for an event");
+                None
+            }
+            Err(e) => {
+                error!("failed to read video event from backend: {}", e);
+                None
+            }
+        }
+    }
+
+    fn take_resource_id_to_notify_eos(&mut self, stream_id: u32) -> Option<u32> {
+        if let Err(e) = self.send_request(VideoRequest::TakeEosNotificationBuffer(stream_id)) {
+            error!("failed to request EOS notification buffer from backend: {}", e);
+            return None;
+        }
+        match self.recv_sync_response() {
+            Ok(VideoResponse::EosNotificationBuffer(resource_id)) => resource_id,
+            Ok(_) => {
+                error!("backend sent an unexpected response to TakeEosNotificationBuffer");
+                None
+            }
+            Err(e) => {
+                error!("failed to read EOS notification buffer from backend: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// The helper-process side. It owns the [`Device`] (and therefore the LibVDA sessions), its event
+/// loop, and the resource bridge, and services requests the frontend forwards over the socket.
+pub struct VideoBackend {
+    device: Box<dyn Device>,
+    sock: MsgSocket<VideoResponse, VideoRequest>,
+    resource_bridge: ResourceRequestSocket,
+}
+
+impl VideoBackend {
+    pub fn new(
+        device: Box<dyn Device>,
+        sock: MsgSocket<VideoResponse, VideoRequest>,
+        resource_bridge: ResourceRequestSocket,
+    ) -> Self {
+        VideoBackend {
+            device,
+            sock,
+            resource_bridge,
+        }
+    }
+
+    /// Runs the backend datapath loop until the frontend sends [`VideoRequest::Stop`] or the socket
+    /// closes: it waits on the command socket and every session's event FD, drives the owned
+    /// `Device`, and streams the resulting responses and events back to the frontend.
+    pub fn run(&mut self) -> VideoResult<()> {
+        let poll_ctx: PollContext<Token> = PollContext::new()
+            .and_then(|pc| {
+                pc.add(&self.sock, Token::CmdQueue)?;
+                Ok(pc)
+            })
+            .map_err(VideoError::SysError)?;
+
+        loop {
+            let events = poll_ctx.wait().map_err(VideoError::SysError)?;
+            for event in events.iter_readable() {
+                match event.token() {
+                    Token::CmdQueue => match self.sock.recv() {
+                        Ok(VideoRequest::ProcessCmd(cmd)) => {
+                            let resp =
+                                self.device
+                                    .process_cmd(cmd, &poll_ctx, &self.resource_bridge);
+                            self.send(VideoResponse::CmdResult(resp))?;
+                        }
+                        Ok(VideoRequest::TakeEosNotificationBuffer(stream_id)) => {
+                            let resource_id = self.device.take_resource_id_to_notify_eos(stream_id);
+                            self.send(VideoResponse::EosNotificationBuffer(resource_id))?;
+                        }
+                        Ok(VideoRequest::Stop) => return Ok(()),
+                        Err(e) => {
+                            error!("backend command socket closed: {}", e);
+                            return Ok(());
+                        }
+                    },
+                    Token::Event { id } => {
+                        if let Some(responses) =
+                            self.device.process_event_fd(id, &self.resource_bridge)
+                        {
+                            for resp in responses {
+                                self.send(VideoResponse::Event(resp))?;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn send(&self, resp: VideoResponse) -> VideoResult<()> {
+        self.sock.send(&resp).map_err(|e| {
+            error!("failed to send video response to frontend: {}", e);
+            VideoError::InvalidOperation
+        })
+    }
+}
+
+/// Runs a `vhost-user-video` helper process body: owns `device` and `resource_bridge` and services
+/// requests from the frontend over `sock` until told to stop. Packaging this as a standalone binary
+/// crate and wiring crosvm's device selection to dial into it are follow-up work; this is the
+/// backend-side entry point that such a binary would call into.
+pub fn run_backend(
+    device: Box<dyn Device>,
+    sock: MsgSocket<VideoResponse, VideoRequest>,
+    resource_bridge: ResourceRequestSocket,
+) -> VideoResult<()> {
+    VideoBackend::new(device, sock, resource_bridge).run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn video_request_stop_round_trips_through_the_wire_format() {
+        let mut buf = vec![0u8; 256];
+        let mut fds = Vec::new();
+        let req = VideoRequest::Stop;
+        let size = req.write_to_buffer(&mut buf, &mut fds).unwrap();
+
+        let (decoded, _) = VideoRequest::read_from_buffer(&buf[..size], &[]).unwrap();
+        assert!(matches!(decoded, VideoRequest::Stop));
+    }
+
+    #[test]
+    fn video_response_eos_notification_buffer_round_trips_through_the_wire_format() {
+        let mut buf = vec![0u8; 256];
+        let mut fds = Vec::new();
+        let resp = VideoResponse::EosNotificationBuffer(Some(7));
+        let size = resp.write_to_buffer(&mut buf, &mut fds).unwrap();
+
+        let (decoded, _) = VideoResponse::read_from_buffer(&buf[..size], &[]).unwrap();
+        match decoded {
+            VideoResponse::EosNotificationBuffer(Some(id)) => assert_eq!(id, 7),
+            _ => panic!("unexpected response variant"),
+        }
+    }
+}